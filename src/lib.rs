@@ -1,7 +1,36 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+mod simd;
+use simd::hamming_distances;
+
+mod persist;
+pub use persist::{MmappedHammingLSH, Pod};
 
 pub type HammingCode = u128;
 
+// Derives a per-table RNG from (seed, table_index).
+struct RandomState {
+    seed: u64
+}
+
+impl RandomState {
+    fn new(seed: u64) -> RandomState {
+        RandomState { seed }
+    }
+
+    fn rng_for_table(&self, table_index: u32) -> StdRng {
+        let derived = self.seed ^ (table_index as u64).wrapping_mul(0x9E3779B97F4A7C15);
+        StdRng::seed_from_u64(derived)
+    }
+}
+
 pub fn hamming_distance(a:HammingCode, b: HammingCode) -> u32 {
     return (a ^ b).count_ones() as u32
 }
@@ -19,32 +48,123 @@ fn hash(planes: &[HammingCode], v: HammingCode) -> u32 {
 }
 
 fn nearest<T: Copy> (candidates:&[Option<(HammingCode, T)>], v: HammingCode) -> Option<(HammingCode, T)> {
+    let present: Vec<(HammingCode, T)> = candidates.iter().filter_map(|c| *c).collect();
+    if present.is_empty() {
+        return None;
+    }
+
+    let codes: Vec<HammingCode> = present.iter().map(|(k, _)| *k).collect();
+    let mut distances = vec![0u32; codes.len()];
+    hamming_distances(v, &codes, &mut distances);
+
     let mut min = u32::MAX;
     let mut best:Option<(HammingCode, T)> = None;
-    for n in candidates.iter() {
-        if let Some((k, i)) = n {
-            let d = hamming_distance(*k, v);
-            if d < min {
-                min = d;
-                best = Some((*k, *i));
-            }
+    for (i, d) in distances.iter().enumerate() {
+        if *d < min {
+            min = *d;
+            best = Some(present[i]);
         }
     }
     best
 }
 
+/// Samples the `k` hyperplanes a table hashes against, as single-bit masks
+/// of `k` distinct bits out of 128, in the order `rng` shuffles them.
+fn sample_hyperplanes(k: u32, rng: &mut impl Rng) -> Vec<HammingCode> {
+    let mut b: Vec<u32> = (0..128).collect();
+    b.shuffle(rng);
+    b[0..k as usize].iter().map(|a| 1 << a).collect()
+}
+
+// Ordered by popcount (then mask) so a BinaryHeap pops the lowest first.
+#[derive(Eq, PartialEq)]
+struct Perturbation {
+    popcount: u32,
+    mask: u32,
+    highest_bit: u32
+}
+
+impl Ord for Perturbation {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.popcount.cmp(&self.popcount).then_with(|| other.mask.cmp(&self.mask))
+    }
+}
+
+impl PartialOrd for Perturbation {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Enumerates up to num_probes distinct, non-empty perturbation masks over
+// the k hash bits in increasing popcount order. Seeding the heap with a
+// single root and growing it by shift/expand avoids generating the same
+// mask from two different roots.
+fn multiprobe_perturbations(k: u32, num_probes: u32) -> Vec<u32> {
+    let mut heap = BinaryHeap::new();
+    if k > 0 {
+        heap.push(Perturbation { popcount: 1, mask: 1, highest_bit: 0 });
+    }
+
+    let mut result = Vec::with_capacity(num_probes as usize);
+    while (result.len() as u32) < num_probes {
+        let p = match heap.pop() {
+            Some(p) => p,
+            None => break
+        };
+        result.push(p.mask);
+
+        if p.highest_bit + 1 < k {
+            heap.push(Perturbation {
+                popcount: p.popcount,
+                mask: (p.mask ^ (1 << p.highest_bit)) | (1 << (p.highest_bit + 1)),
+                highest_bit: p.highest_bit + 1
+            });
+            heap.push(Perturbation {
+                popcount: p.popcount + 1,
+                mask: p.mask | (1 << (p.highest_bit + 1)),
+                highest_bit: p.highest_bit + 1
+            });
+        }
+    }
+    result
+}
+
+// Ordered by distance, farthest on top, so get_k's heap evicts it first.
+struct KNearestEntry {
+    distance: u32,
+    code: HammingCode,
+    index: usize
+}
+
+impl PartialEq for KNearestEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.distance == other.distance
+    }
+}
+
+impl Eq for KNearestEntry {}
+
+impl Ord for KNearestEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.cmp(&other.distance)
+    }
+}
+
+impl PartialOrd for KNearestEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 struct HammingTable<T> {
     hyperplanes: Vec<HammingCode>,
     buckets: Vec<Vec<Option<(HammingCode, T)>>>
 }
 
 impl<T:Clone + Copy> HammingTable<T> {
-    fn new(k: u32) -> HammingTable<T> {
-        let mut b: Vec<u32> = (0..128).collect();
-        b.shuffle(&mut rand::thread_rng());
-
-        let hyperplanes: Vec<HammingCode> = 
-            b[0..k as usize].iter().map(|a| 1 << a).collect();
+    fn new(k: u32, rng: &mut impl Rng) -> HammingTable<T> {
+        let hyperplanes = sample_hyperplanes(k, rng);
         let buckets = vec!(Vec::<Option<(HammingCode, T)>>::new(); 1 << k as usize);
 
         HammingTable {
@@ -62,26 +182,41 @@ impl<T:Clone + Copy> HammingTable<T> {
         self.buckets[h as usize].push(Some((k, v)));
     }
 
-    fn get(&self, k: HammingCode) -> Option<(HammingCode, T)> {
+    // Returns every entry in the bucket k hashes to; the caller reduces it.
+    fn get(&self, k: HammingCode) -> &[Option<(HammingCode, T)>] {
         let h = self.hash(k);
-        nearest(&self.buckets[h as usize][..], k)
+        &self.buckets[h as usize][..]
     }
 }
 
 pub struct HammingLSH<T> {
     tables: Vec<HammingTable<usize>>,
-    data: Vec<T>
+    data: Vec<T>,
+    k: u32,
+    l: u32,
+    seed: u64
 }
 
 impl<T:Clone> HammingLSH<T> {
+    // Hyperplanes are not reproducible across runs; see with_seed.
     pub fn new(k: u32, l: u32) -> HammingLSH<T> {
+        let seed = rand::thread_rng().gen::<u64>();
+        HammingLSH::with_seed(k, l, seed)
+    }
+
+    pub fn with_seed(k: u32, l: u32, seed: u64) -> HammingLSH<T> {
+        let state = RandomState::new(seed);
         let mut tables = Vec::<HammingTable<usize>>::new();
-        for _ in 0..l {
-            tables.push(HammingTable::<usize>::new(k));
+        for i in 0..l {
+            let mut rng = state.rng_for_table(i);
+            tables.push(HammingTable::<usize>::new(k, &mut rng));
         }
         HammingLSH {
             tables: tables,
-            data: Vec::<T>::new()
+            data: Vec::<T>::new(),
+            k,
+            l,
+            seed
         }
     }
 
@@ -93,15 +228,110 @@ impl<T:Clone> HammingLSH<T> {
         }
     }
 
+    // Hashes a batch of items into every table in parallel. Requires the
+    // parallel feature.
+    #[cfg(feature = "parallel")]
+    pub fn insert_batch(&mut self, items: &[(HammingCode, T)]) where T: Send + Sync {
+        let start = self.data.len();
+        self.data.extend(items.iter().map(|(_, v)| v.clone()));
+        let keyed: Vec<(HammingCode, usize)> = items.iter().enumerate()
+            .map(|(i, (k, _))| (*k, start + i))
+            .collect();
+        self.tables.par_iter_mut().for_each(|t| {
+            for (k, i) in keyed.iter() {
+                t.insert(*k, *i);
+            }
+        });
+    }
+
+    #[cfg(not(feature = "parallel"))]
     pub fn get(&self, v: HammingCode) -> Option<(HammingCode, &T)> {
-        let c:Vec<Option<(HammingCode, usize)>> = self.tables.iter()
-            .map(|t| t.get(v))
+        self.get_k(v, 1).into_iter().next()
+    }
+
+    // Scans all l tables' buckets in parallel before reducing to the
+    // single nearest candidate.
+    #[cfg(feature = "parallel")]
+    pub fn get(&self, v: HammingCode) -> Option<(HammingCode, &T)>
+    where T: Sync {
+        let c:Vec<Option<(HammingCode, usize)>> = self.tables.par_iter()
+            .flat_map_iter(|t| t.get(v).iter().copied())
             .collect();
         match nearest(&c[..], v) {
             Some((k, i)) => Some((k, &self.data[i])),
             _ => None
         }
     }
+
+    // Returns the k closest stored codes to v in ascending Hamming
+    // distance, deduped by data index via a bounded max-heap of size k.
+    pub fn get_k(&self, v: HammingCode, k: usize) -> Vec<(HammingCode, &T)> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        let mut seen = HashSet::new();
+        let mut heap: BinaryHeap<KNearestEntry> = BinaryHeap::with_capacity(k);
+
+        for t in self.tables.iter() {
+            for (code, i) in t.get(v).iter().filter_map(|e| *e) {
+                if !seen.insert(i) {
+                    continue;
+                }
+
+                let distance = hamming_distance(code, v);
+                if heap.len() < k {
+                    heap.push(KNearestEntry { distance, code, index: i });
+                } else if heap.peek().is_some_and(|worst| distance < worst.distance) {
+                    heap.pop();
+                    heap.push(KNearestEntry { distance, code, index: i });
+                }
+            }
+        }
+
+        let mut entries = heap.into_vec();
+        entries.sort_by_key(|e| e.distance);
+        entries.into_iter().map(|e| (e.code, &self.data[e.index])).collect()
+    }
+
+    // Like get, but also scans up to num_probes neighbouring buckets per
+    // table (at most 2^k - 1 of them exist).
+    // num_probes = 0 is exactly get.
+    pub fn get_multiprobe(&self, v: HammingCode, num_probes: u32) -> Option<(HammingCode, &T)> {
+        let mut seen = HashSet::new();
+        let mut candidates: Vec<Option<(HammingCode, usize)>> = Vec::new();
+
+        for t in self.tables.iter() {
+            let h = t.hash(v);
+            let perturbations = multiprobe_perturbations(t.hyperplanes.len() as u32, num_probes);
+            let bucket_ids = std::iter::once(h).chain(perturbations.iter().map(|m| h ^ m));
+
+            for bucket_id in bucket_ids {
+                for entry @ (_, i) in t.buckets[bucket_id as usize].iter().filter_map(|e| *e) {
+                    if seen.insert(i) {
+                        candidates.push(Some(entry));
+                    }
+                }
+            }
+        }
+
+        match nearest(&candidates[..], v) {
+            Some((k, i)) => Some((k, &self.data[i])),
+            _ => None
+        }
+    }
+}
+
+impl<T: Pod> HammingLSH<T> {
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        persist::save(self, path)
+    }
+
+    /// Reopens an index written by [`HammingLSH::save`] without rebuilding
+    /// the hash tables.
+    pub fn open(path: impl AsRef<std::path::Path>) -> std::io::Result<MmappedHammingLSH<T>> {
+        persist::open(path)
+    }
 }
 
 pub fn hamming_peturb(v: HammingCode, bits:u32) -> HammingCode {
@@ -141,4 +371,98 @@ mod tests {
                                 Some((0b001, 1)));
         assert_eq!(nearest::<u32>(&[None], 0b001), None);
     }
+
+    #[test]
+    fn test_with_seed_is_reproducible() {
+        let a = HammingLSH::<u32>::with_seed(8, 4, 42);
+        let b = HammingLSH::<u32>::with_seed(8, 4, 42);
+        for (ta, tb) in a.tables.iter().zip(b.tables.iter()) {
+            assert_eq!(ta.hyperplanes, tb.hyperplanes);
+        }
+    }
+
+    #[test]
+    fn test_with_seed_tables_differ() {
+        let a = HammingLSH::<u32>::with_seed(8, 4, 42);
+        assert_ne!(a.tables[0].hyperplanes, a.tables[1].hyperplanes);
+    }
+
+    #[test]
+    fn test_multiprobe_perturbations_ordered_by_popcount() {
+        let masks = multiprobe_perturbations(3, 7);
+        assert_eq!(masks.len(), 7);
+        let popcounts: Vec<u32> = masks.iter().map(|m| m.count_ones()).collect();
+        assert!(popcounts.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(masks[masks.len() - 1], 0b111);
+    }
+
+    #[test]
+    fn test_multiprobe_perturbations_are_distinct_even_when_budget_exceeds_total() {
+        // Only 2^3 - 1 = 7 distinct nonempty masks exist over 3 bits, so
+        // asking for more than that must not return duplicates.
+        let masks = multiprobe_perturbations(3, 20);
+        assert_eq!(masks.len(), 7);
+        let unique: HashSet<u32> = masks.iter().copied().collect();
+        assert_eq!(unique.len(), 7);
+    }
+
+    #[test]
+    fn test_get_multiprobe_zero_probes_matches_get() {
+        let mut lsh = HammingLSH::<u32>::with_seed(6, 3, 99);
+        lsh.insert(0b0101, 1);
+        lsh.insert(0b1100, 2);
+        lsh.insert(0b1111, 3);
+
+        assert_eq!(lsh.get(0b1100), lsh.get_multiprobe(0b1100, 0));
+    }
+
+    #[test]
+    fn test_get_k_ascending_distance_and_dedup() {
+        // k = 0 means every code falls into each table's single bucket,
+        // so every inserted item is a candidate regardless of seed.
+        let mut lsh = HammingLSH::<u32>::with_seed(0, 3, 11);
+        lsh.insert(0b0000, 1);
+        lsh.insert(0b0001, 2);
+        lsh.insert(0b0011, 3);
+
+        let top = lsh.get_k(0b0000, 2);
+        assert_eq!(top.len(), 2);
+        assert_eq!(*top[0].1, 1);
+        assert_eq!(*top[1].1, 2);
+
+        assert_eq!(lsh.get_k(0b0000, 0), Vec::new());
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn test_insert_batch_matches_sequential_insert() {
+        let items = [(0b0001, 1u32), (0b0010, 2), (0b0100, 3), (0b1000, 4)];
+
+        let mut sequential = HammingLSH::<u32>::with_seed(5, 3, 21);
+        for (k, v) in items.iter() {
+            sequential.insert(*k, *v);
+        }
+
+        let mut batched = HammingLSH::<u32>::with_seed(5, 3, 21);
+        batched.insert_batch(&items);
+
+        for (k, _) in items.iter() {
+            assert_eq!(sequential.get(*k), batched.get(*k));
+        }
+    }
+
+    #[test]
+    fn test_save_and_open_roundtrip() {
+        let mut lsh = HammingLSH::<u32>::with_seed(5, 4, 123);
+        lsh.insert(0b01011, 10);
+        lsh.insert(0b10100, 20);
+        lsh.insert(0b01111, 30);
+
+        let path = std::env::temp_dir().join("hamming_lsh_test_roundtrip.idx");
+        lsh.save(&path).unwrap();
+        let opened = HammingLSH::<u32>::open(&path).unwrap();
+
+        assert_eq!(lsh.get(0b01011), opened.get(0b01011));
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::{hamming_distance, hash, sample_hyperplanes, HammingCode, HammingLSH, RandomState};
+
+const MAGIC: u32 = 0x484C_5348; // "HLSH"
+
+/// # Safety
+/// Implementors must have no padding bytes and no pointers or references,
+/// so an arbitrary bit pattern read back from disk is always valid.
+pub unsafe trait Pod: Copy {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for u128 {}
+unsafe impl Pod for usize {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
+unsafe impl Pod for i128 {}
+unsafe impl Pod for isize {}
+unsafe impl Pod for f32 {}
+unsafe impl Pod for f64 {}
+
+// A bucket entry as stored on disk: the code and its data-section index.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    code: HammingCode,
+    index: u64
+}
+
+struct TableSection {
+    bucket_offsets_offset: usize,
+    records_offset: usize
+}
+
+/// A read-only `HammingLSH` view backed by a memory-mapped file. Built by
+/// [`HammingLSH::open`].
+pub struct MmappedHammingLSH<T> {
+    mmap: Mmap,
+    l: u32,
+    hyperplanes: Vec<Vec<HammingCode>>,
+    data_offset: usize,
+    table_sections: Vec<TableSection>,
+    _marker: std::marker::PhantomData<T>
+}
+
+// Alignment every section is padded to, so slicing the mmap into
+// &u64/&Record/&T never produces a misaligned pointer.
+fn section_align<T>() -> usize {
+    std::cmp::max(std::mem::align_of::<T>(), std::mem::align_of::<Record>())
+}
+
+fn align_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+fn pad_to(buf: &mut Vec<u8>, align: usize) {
+    let padded = align_up(buf.len(), align);
+    buf.resize(padded, 0);
+}
+
+fn read_u32(buf: &[u8], cursor: &mut usize) -> u32 {
+    let v = u32::from_le_bytes(buf[*cursor..*cursor + 4].try_into().unwrap());
+    *cursor += 4;
+    v
+}
+
+fn read_u64(buf: &[u8], cursor: &mut usize) -> u64 {
+    let v = u64::from_le_bytes(buf[*cursor..*cursor + 8].try_into().unwrap());
+    *cursor += 8;
+    v
+}
+
+pub(crate) fn save<T: Pod>(lsh: &HammingLSH<T>, path: impl AsRef<Path>) -> io::Result<()> {
+    let num_buckets = 1usize << lsh.k;
+    let align = section_align::<T>();
+
+    let mut bucket_offsets = Vec::with_capacity(lsh.tables.len());
+    let mut record_counts = Vec::with_capacity(lsh.tables.len());
+    for t in lsh.tables.iter() {
+        let mut offsets = Vec::with_capacity(num_buckets + 1);
+        let mut acc = 0u64;
+        offsets.push(0u64);
+        for bucket in t.buckets.iter() {
+            acc += bucket.len() as u64;
+            offsets.push(acc);
+        }
+        record_counts.push(acc);
+        bucket_offsets.push(offsets);
+    }
+
+    let mut buf: Vec<u8> = Vec::new();
+    buf.extend_from_slice(&MAGIC.to_le_bytes());
+    buf.extend_from_slice(&lsh.k.to_le_bytes());
+    buf.extend_from_slice(&lsh.l.to_le_bytes());
+    buf.extend_from_slice(&lsh.seed.to_le_bytes());
+    buf.extend_from_slice(&(lsh.data.len() as u64).to_le_bytes());
+    for count in &record_counts {
+        buf.extend_from_slice(&count.to_le_bytes());
+    }
+
+    pad_to(&mut buf, align);
+    let data_bytes = unsafe {
+        std::slice::from_raw_parts(
+            lsh.data.as_ptr() as *const u8,
+            lsh.data.len() * std::mem::size_of::<T>()
+        )
+    };
+    buf.extend_from_slice(data_bytes);
+
+    for (t, offsets) in lsh.tables.iter().zip(bucket_offsets.iter()) {
+        pad_to(&mut buf, align);
+        for offset in offsets {
+            buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        pad_to(&mut buf, align);
+        for bucket in t.buckets.iter() {
+            for (code, index) in bucket.iter().filter_map(|e| *e) {
+                let record = Record { code, index: index as u64 };
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(
+                        &record as *const Record as *const u8,
+                        std::mem::size_of::<Record>()
+                    )
+                };
+                buf.extend_from_slice(bytes);
+            }
+        }
+    }
+
+    let mut f = File::create(path)?;
+    f.write_all(&buf)
+}
+
+pub(crate) fn open<T: Pod>(path: impl AsRef<Path>) -> io::Result<MmappedHammingLSH<T>> {
+    let file = File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let align = section_align::<T>();
+
+    let mut cursor = 0usize;
+    let magic = read_u32(&mmap, &mut cursor);
+    if magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a HammingLSH index file"));
+    }
+    let k = read_u32(&mmap, &mut cursor);
+    let l = read_u32(&mmap, &mut cursor);
+    let seed = read_u64(&mmap, &mut cursor);
+    let data_len = read_u64(&mmap, &mut cursor) as usize;
+    let record_counts: Vec<u64> = (0..l).map(|_| read_u64(&mmap, &mut cursor)).collect();
+
+    cursor = align_up(cursor, align);
+    let data_offset = cursor;
+    cursor += data_len * std::mem::size_of::<T>();
+
+    let state = RandomState::new(seed);
+    let num_buckets = 1usize << k;
+    let mut hyperplanes = Vec::with_capacity(l as usize);
+    let mut table_sections = Vec::with_capacity(l as usize);
+    for i in 0..l {
+        let mut rng = state.rng_for_table(i);
+        hyperplanes.push(sample_hyperplanes(k, &mut rng));
+
+        cursor = align_up(cursor, align);
+        let bucket_offsets_offset = cursor;
+        cursor += (num_buckets + 1) * std::mem::size_of::<u64>();
+        cursor = align_up(cursor, align);
+        let records_offset = cursor;
+        cursor += record_counts[i as usize] as usize * std::mem::size_of::<Record>();
+        table_sections.push(TableSection { bucket_offsets_offset, records_offset });
+    }
+
+    Ok(MmappedHammingLSH {
+        mmap,
+        l,
+        hyperplanes,
+        data_offset,
+        table_sections,
+        _marker: std::marker::PhantomData
+    })
+}
+
+impl<T: Pod> MmappedHammingLSH<T> {
+    fn bucket_records(&self, table_index: u32, bucket: u32) -> &[Record] {
+        let section = &self.table_sections[table_index as usize];
+        let offsets_ptr = unsafe {
+            self.mmap.as_ptr().add(section.bucket_offsets_offset) as *const u64
+        };
+        let start = unsafe { *offsets_ptr.add(bucket as usize) } as usize;
+        let end = unsafe { *offsets_ptr.add(bucket as usize + 1) } as usize;
+        let records_ptr = unsafe {
+            self.mmap.as_ptr().add(section.records_offset) as *const Record
+        };
+        unsafe { std::slice::from_raw_parts(records_ptr.add(start), end - start) }
+    }
+
+    // Same lookup as HammingLSH::get, scanning bucket ranges sliced
+    // directly out of the mapped file.
+    pub fn get(&self, v: HammingCode) -> Option<(HammingCode, &T)> {
+        let data_ptr = unsafe { self.mmap.as_ptr().add(self.data_offset) as *const T };
+
+        let mut min = u32::MAX;
+        let mut best: Option<(HammingCode, &T)> = None;
+        for i in 0..self.l {
+            let bucket = hash(&self.hyperplanes[i as usize], v);
+            for record in self.bucket_records(i, bucket) {
+                let d = hamming_distance(record.code, v);
+                if d < min {
+                    min = d;
+                    best = Some((record.code, unsafe { &*data_ptr.add(record.index as usize) }));
+                }
+            }
+        }
+        best
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{hamming_distance, HammingCode};
+
+/// Popcount of each nibble value 0..15, used twice per 256-bit lane since
+/// `_mm256_shuffle_epi8` shuffles within each 128-bit half independently.
+#[cfg(target_arch = "x86_64")]
+const NIBBLE_POPCOUNT: [u8; 32] = [
+    0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+    0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+];
+
+/// Computes the Hamming distance from `query` to every entry of
+/// `candidates`, writing the results into `out`. Uses a runtime-detected
+/// AVX2 kernel when available, falling back to the scalar `count_ones`
+/// loop otherwise.
+pub(crate) fn hamming_distances(query: HammingCode, candidates: &[HammingCode], out: &mut [u32]) {
+    assert_eq!(candidates.len(), out.len());
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            unsafe { hamming_distances_avx2(query, candidates, out) };
+            return;
+        }
+    }
+
+    hamming_distances_scalar(query, candidates, out);
+}
+
+fn hamming_distances_scalar(query: HammingCode, candidates: &[HammingCode], out: &mut [u32]) {
+    for (c, o) in candidates.iter().zip(out.iter_mut()) {
+        *o = hamming_distance(query, *c);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn hamming_distances_avx2(query: HammingCode, candidates: &[HammingCode], out: &mut [u32]) {
+    use std::arch::x86_64::*;
+
+    let lookup = _mm256_loadu_si256(NIBBLE_POPCOUNT.as_ptr() as *const __m256i);
+    let low_mask = _mm256_set1_epi8(0x0F);
+    let query_bytes = _mm_loadu_si128(&query as *const HammingCode as *const __m128i);
+    let query_bcast = _mm256_broadcastsi128_si256(query_bytes);
+    let zero = _mm256_setzero_si256();
+
+    let mut chunks = candidates.chunks_exact(2);
+    let mut out_chunks = out.chunks_exact_mut(2);
+
+    for (pair, out_pair) in (&mut chunks).zip(&mut out_chunks) {
+        let packed = _mm256_loadu_si256(pair.as_ptr() as *const __m256i);
+        let xored = _mm256_xor_si256(packed, query_bcast);
+
+        let lo_nibbles = _mm256_and_si256(xored, low_mask);
+        let hi_nibbles = _mm256_and_si256(_mm256_srli_epi16(xored, 4), low_mask);
+
+        let lo_counts = _mm256_shuffle_epi8(lookup, lo_nibbles);
+        let hi_counts = _mm256_shuffle_epi8(lookup, hi_nibbles);
+        let byte_counts = _mm256_add_epi8(lo_counts, hi_counts);
+
+        let lane_sums = _mm256_sad_epu8(byte_counts, zero);
+        let mut sums = [0u64; 4];
+        _mm256_storeu_si256(sums.as_mut_ptr() as *mut __m256i, lane_sums);
+
+        out_pair[0] = (sums[0] + sums[1]) as u32;
+        out_pair[1] = (sums[2] + sums[3]) as u32;
+    }
+
+    hamming_distances_scalar(query, chunks.remainder(), out_chunks.into_remainder());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(query: HammingCode, candidates: &[HammingCode]) {
+        let mut dispatched = vec![0u32; candidates.len()];
+        hamming_distances(query, candidates, &mut dispatched);
+
+        let mut scalar = vec![0u32; candidates.len()];
+        hamming_distances_scalar(query, candidates, &mut scalar);
+
+        assert_eq!(dispatched, scalar, "query={query:#x} candidates={candidates:?}");
+    }
+
+    #[test]
+    fn test_hamming_distances_zero_length() {
+        check(0, &[]);
+        check(u128::MAX, &[]);
+    }
+
+    #[test]
+    fn test_hamming_distances_even_and_odd_lengths() {
+        let candidates: Vec<HammingCode> = (0..10).map(|i| i as HammingCode).collect();
+        for len in 0..=candidates.len() {
+            check(0b1010, &candidates[..len]);
+        }
+    }
+
+    #[test]
+    fn test_hamming_distances_bit_patterns() {
+        let alternating: HammingCode = 0xAAAA_AAAA_AAAA_AAAA_AAAA_AAAA_AAAA_AAAA;
+        let candidates = [0, u128::MAX, alternating, !alternating, 1, 1 << 127];
+
+        check(0, &candidates);
+        check(u128::MAX, &candidates);
+        check(alternating, &candidates);
+    }
+}